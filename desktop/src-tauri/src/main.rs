@@ -4,8 +4,74 @@
 // Prevents additional console window on Windows in release
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use tauri::http::Response;
 use tauri::Manager;
 
+/// An in-memory image payload staged for one-shot retrieval over the
+/// `pic2pic://` URI scheme.
+struct ImageBuffer {
+    mime: String,
+    buf: Vec<u8>,
+}
+
+/// Staged images awaiting pickup by the `pic2pic://` protocol handler,
+/// keyed by a generated opaque id.
+#[derive(Default)]
+struct StagedImages(Mutex<HashMap<String, ImageBuffer>>);
+
+/// Directories the fs commands are allowed to touch, mirroring Tauri's
+/// asset-protocol scope model.
+///
+/// Seeded in `setup` with the app's own data/cache directories, and grown at
+/// runtime via [`allow_path`] whenever the user opens a folder through the
+/// dialog plugin. Every fs command must canonicalize its incoming path and
+/// check it against this scope before touching disk.
+#[derive(Default)]
+struct FsScope(Mutex<Vec<std::path::PathBuf>>);
+
+impl FsScope {
+    fn allow(&self, root: std::path::PathBuf) {
+        self.0.lock().unwrap().push(root);
+    }
+
+    /// Canonicalizes `path` and checks it falls under one of the allowed
+    /// roots, defeating `../` traversal. Returns the canonical path on
+    /// success.
+    ///
+    /// `path` need not exist yet (e.g. a new file about to be written); in
+    /// that case its parent directory is canonicalized and checked instead.
+    fn check(&self, path: &str) -> Result<std::path::PathBuf, String> {
+        let path = std::path::Path::new(path);
+        let canonical = if path.exists() {
+            std::fs::canonicalize(path).map_err(|e| e.to_string())?
+        } else {
+            let parent = path
+                .parent()
+                .filter(|p| !p.as_os_str().is_empty())
+                .ok_or_else(|| "path has no parent directory".to_string())?;
+            let file_name = path
+                .file_name()
+                .ok_or_else(|| "path has no file name".to_string())?;
+            std::fs::canonicalize(parent)
+                .map_err(|e| e.to_string())?
+                .join(file_name)
+        };
+
+        let roots = self.0.lock().unwrap();
+        if roots.iter().any(|root| canonical.starts_with(root)) {
+            Ok(canonical)
+        } else {
+            Err(format!(
+                "path {} is outside the allowed scope",
+                canonical.display()
+            ))
+        }
+    }
+}
+
 /// Command to get system information
 #[tauri::command]
 fn get_system_info() -> serde_json::Value {
@@ -18,19 +84,26 @@ fn get_system_info() -> serde_json::Value {
 
 /// Command to read a local file
 #[tauri::command]
-async fn read_local_file(path: String) -> Result<Vec<u8>, String> {
+async fn read_local_file(scope: tauri::State<'_, FsScope>, path: String) -> Result<Vec<u8>, String> {
+    let path = scope.check(&path)?;
     std::fs::read(&path).map_err(|e| e.to_string())
 }
 
 /// Command to write a local file
 #[tauri::command]
-async fn write_local_file(path: String, contents: Vec<u8>) -> Result<(), String> {
+async fn write_local_file(
+    scope: tauri::State<'_, FsScope>,
+    path: String,
+    contents: Vec<u8>,
+) -> Result<(), String> {
+    let path = scope.check(&path)?;
     std::fs::write(&path, &contents).map_err(|e| e.to_string())
 }
 
 /// Command to list directory contents
 #[tauri::command]
-async fn list_directory(path: String) -> Result<Vec<String>, String> {
+async fn list_directory(scope: tauri::State<'_, FsScope>, path: String) -> Result<Vec<String>, String> {
+    let path = scope.check(&path)?;
     let entries = std::fs::read_dir(&path).map_err(|e| e.to_string())?;
     
     let mut files = Vec::new();
@@ -45,25 +118,350 @@ async fn list_directory(path: String) -> Result<Vec<String>, String> {
     Ok(files)
 }
 
+/// Default set of image extensions scanned for when the caller doesn't
+/// supply an explicit allow-list.
+const DEFAULT_IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "webp", "bmp", "tiff"];
+
+/// Command to recursively enumerate every image under `root` for batch
+/// processing jobs.
+///
+/// Unlike [`list_directory`], this walks the full tree (via `walkdir`),
+/// collecting absolute paths whose extension matches `extensions`
+/// case-insensitively (defaulting to `DEFAULT_IMAGE_EXTENSIONS` when empty),
+/// optionally capped at `max_depth`. Directories that can't be read are
+/// skipped rather than aborting the whole scan.
+#[tauri::command]
+async fn scan_images(
+    scope: tauri::State<'_, FsScope>,
+    root: String,
+    extensions: Vec<String>,
+    max_depth: Option<usize>,
+) -> Result<Vec<String>, String> {
+    let root = scope.check(&root)?;
+
+    let allow_list: Vec<String> = if extensions.is_empty() {
+        DEFAULT_IMAGE_EXTENSIONS
+            .iter()
+            .map(|e| e.to_string())
+            .collect()
+    } else {
+        extensions.iter().map(|e| e.to_lowercase()).collect()
+    };
+
+    let mut walker = walkdir::WalkDir::new(&root);
+    if let Some(depth) = max_depth {
+        walker = walker.max_depth(depth);
+    }
+
+    let mut files = Vec::new();
+    for entry in walker.into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let matches = entry
+            .path()
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| allow_list.contains(&e.to_lowercase()))
+            .unwrap_or(false);
+
+        if matches {
+            if let Ok(absolute) = entry.path().canonicalize() {
+                files.push(absolute.to_string_lossy().into_owned());
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Resolves the on-disk cache path for a content hash, probing for whichever
+/// extension the cached entry was written with.
+fn find_cached_entry(cache_dir: &std::path::Path, hash: &str) -> Option<std::path::PathBuf> {
+    let entries = std::fs::read_dir(cache_dir).ok()?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.file_stem().and_then(|s| s.to_str()) == Some(hash) {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Command to fetch-or-reuse a content-addressed cache entry for a source
+/// image, given either a local path or an `http(s)` URL.
+///
+/// The bytes are hashed with SHA-256 and written to
+/// `<app_cache_dir>/<hash>.<ext>` on first sight; subsequent calls for the
+/// same content return the existing cache file instead of writing a new one.
+/// The source is still read (or downloaded) on every call, since the hash is
+/// taken over its bytes.
+#[tauri::command]
+async fn cache_image(
+    app: tauri::AppHandle,
+    scope: tauri::State<'_, FsScope>,
+    source: String,
+) -> Result<String, String> {
+    use sha2::{Digest, Sha256};
+
+    let bytes = if source.starts_with("http://") || source.starts_with("https://") {
+        reqwest::get(&source)
+            .await
+            .map_err(|e| e.to_string())?
+            .bytes()
+            .await
+            .map_err(|e| e.to_string())?
+            .to_vec()
+    } else {
+        let path = scope.check(&source)?;
+        std::fs::read(&path).map_err(|e| e.to_string())?
+    };
+
+    let hash = format!("{:x}", Sha256::digest(&bytes));
+
+    let cache_dir = app.path().app_cache_dir().map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(&cache_dir).map_err(|e| e.to_string())?;
+
+    if let Some(existing) = find_cached_entry(&cache_dir, &hash) {
+        return Ok(existing.to_string_lossy().into_owned());
+    }
+
+    let source_path = source.split(['?', '#']).next().unwrap_or(&source);
+    let ext = std::path::Path::new(source_path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("bin");
+    let dest = cache_dir.join(format!("{}.{}", hash, ext));
+    std::fs::write(&dest, &bytes).map_err(|e| e.to_string())?;
+
+    Ok(dest.to_string_lossy().into_owned())
+}
+
+/// Command to look up a previously cached image by its content hash without
+/// fetching or writing anything.
+#[tauri::command]
+fn cache_lookup(app: tauri::AppHandle, hash: String) -> Option<String> {
+    let cache_dir = app.path().app_cache_dir().ok()?;
+    find_cached_entry(&cache_dir, &hash).map(|p| p.to_string_lossy().into_owned())
+}
+
+/// Command to wipe the on-disk image cache, returning the number of bytes
+/// freed.
+#[tauri::command]
+fn clear_cache(app: tauri::AppHandle) -> Result<u64, String> {
+    let cache_dir = app.path().app_cache_dir().map_err(|e| e.to_string())?;
+
+    let mut freed = 0u64;
+    let entries = match std::fs::read_dir(&cache_dir) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(0),
+    };
+    for entry in entries.flatten() {
+        let len = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        if std::fs::remove_file(entry.path()).is_ok() {
+            freed += len;
+        }
+    }
+
+    Ok(freed)
+}
+
+/// Command to open a native folder picker and grant the fs commands access
+/// to whatever the user selects.
+///
+/// The picker is driven here, backend-side, rather than accepting a
+/// frontend-supplied path: the grant is bound to a real user gesture through
+/// `tauri_plugin_dialog`, so a compromised renderer cannot self-grant access
+/// to an arbitrary directory. Returns the granted path, or `None` if the
+/// user dismissed the dialog.
+#[tauri::command]
+async fn allow_path(
+    app: tauri::AppHandle,
+    scope: tauri::State<'_, FsScope>,
+) -> Result<Option<String>, String> {
+    use tauri_plugin_dialog::DialogExt;
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    app.dialog().file().pick_folder(move |folder| {
+        let _ = tx.send(folder);
+    });
+    let picked = rx.recv().map_err(|e| e.to_string())?;
+
+    let Some(folder) = picked else {
+        return Ok(None);
+    };
+    let path = folder.into_path().map_err(|e| e.to_string())?;
+    let canonical = std::fs::canonicalize(&path).map_err(|e| e.to_string())?;
+
+    scope.allow(canonical.clone());
+    Ok(Some(canonical.to_string_lossy().into_owned()))
+}
+
+/// Command to stage an image for zero-copy retrieval by the frontend.
+///
+/// The bytes are held in memory under a generated key and handed back as a
+/// `pic2pic://<key>` URL that can be dropped straight into an `<img src>`,
+/// avoiding a base64 round-trip through the IPC bridge for large images.
+#[tauri::command]
+fn stage_image(state: tauri::State<StagedImages>, contents: Vec<u8>, mime: String) -> String {
+    let key = uuid::Uuid::new_v4().to_string();
+    state
+        .0
+        .lock()
+        .unwrap()
+        .insert(key.clone(), ImageBuffer { mime, buf: contents });
+    format!("pic2pic://{}", key)
+}
+
 fn main() {
     tauri::Builder::default()
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_dialog::init())
+        .manage(StagedImages::default())
+        .manage(FsScope::default())
         .invoke_handler(tauri::generate_handler![
             get_system_info,
             read_local_file,
             write_local_file,
-            list_directory
+            list_directory,
+            scan_images,
+            allow_path,
+            stage_image,
+            cache_image,
+            cache_lookup,
+            clear_cache
         ])
+        .register_uri_scheme_protocol("pic2pic", |app, request| {
+            // On Windows/Android, Tauri serves custom schemes over
+            // `http://pic2pic.localhost/<key>`, so the key lands in the path
+            // rather than the host; fall back to the path in that case.
+            let uri = request.uri();
+            let host = uri.host().unwrap_or_default();
+            let key = if host.is_empty() || host == "pic2pic.localhost" {
+                uri.path().trim_start_matches('/')
+            } else {
+                host
+            };
+            let state = app.state::<StagedImages>();
+            let staged = state.0.lock().unwrap().remove(key);
+
+            match staged {
+                Some(image) => Response::builder()
+                    .header("Content-Type", image.mime)
+                    .body(image.buf)
+                    .unwrap(),
+                None => Response::builder()
+                    .status(404)
+                    .header("Content-Type", "text/plain")
+                    .body(b"not found".to_vec())
+                    .unwrap(),
+            }
+        })
         .setup(|app| {
             // Get the main window
             let window = app.get_webview_window("main").unwrap();
-            
+
             // Set window title
             window.set_title("pic2pic-nextgen v2.0.0").unwrap();
-            
+
+            // Seed the fs sandbox with the app's own data/cache directories;
+            // folders the user opens via the dialog plugin are added later
+            // through `allow_path`.
+            let scope = app.state::<FsScope>();
+            if let Ok(data_dir) = app.path().app_data_dir() {
+                let _ = std::fs::create_dir_all(&data_dir);
+                if let Ok(canonical) = std::fs::canonicalize(&data_dir) {
+                    scope.allow(canonical);
+                }
+            }
+            if let Ok(cache_dir) = app.path().app_cache_dir() {
+                let _ = std::fs::create_dir_all(&cache_dir);
+                if let Ok(canonical) = std::fs::canonicalize(&cache_dir) {
+                    scope.allow(canonical);
+                }
+            }
+
             Ok(())
         })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::FsScope;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+    /// Creates a fresh, empty directory under the OS temp dir for a single
+    /// test to own.
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+        let dir = std::env::temp_dir().join(format!("pic2pic-fsscope-test-{}-{}-{}", std::process::id(), n, name));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn accepts_path_under_allowed_root() {
+        let root = temp_dir("allowed");
+        let file = root.join("image.png");
+        std::fs::write(&file, b"data").unwrap();
+
+        let scope = FsScope::default();
+        scope.allow(std::fs::canonicalize(&root).unwrap());
+
+        assert!(scope.check(file.to_str().unwrap()).is_ok());
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn rejects_dot_dot_escape_from_allowed_root() {
+        let root = temp_dir("allowed");
+        let outside = temp_dir("outside");
+        let secret = outside.join("secret.png");
+        std::fs::write(&secret, b"data").unwrap();
+
+        let scope = FsScope::default();
+        scope.allow(std::fs::canonicalize(&root).unwrap());
+
+        let traversal = root.join("..").join(outside.file_name().unwrap()).join("secret.png");
+        assert!(scope.check(traversal.to_str().unwrap()).is_err());
+
+        std::fs::remove_dir_all(&root).ok();
+        std::fs::remove_dir_all(&outside).ok();
+    }
+
+    #[test]
+    fn accepts_not_yet_existing_file_under_allowed_root() {
+        let root = temp_dir("allowed");
+        let scope = FsScope::default();
+        scope.allow(std::fs::canonicalize(&root).unwrap());
+
+        let new_file = root.join("not-written-yet.png");
+        assert!(scope.check(new_file.to_str().unwrap()).is_ok());
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn rejects_sibling_directory_with_shared_prefix() {
+        let base = temp_dir("siblings-base");
+        let allowed = base.join("allowed");
+        let evil = base.join("allowed-evil");
+        std::fs::create_dir_all(&allowed).unwrap();
+        std::fs::create_dir_all(&evil).unwrap();
+        let secret = evil.join("secret.png");
+        std::fs::write(&secret, b"data").unwrap();
+
+        let scope = FsScope::default();
+        scope.allow(std::fs::canonicalize(&allowed).unwrap());
+
+        assert!(scope.check(secret.to_str().unwrap()).is_err());
+
+        std::fs::remove_dir_all(&base).ok();
+    }
+}